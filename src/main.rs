@@ -1,18 +1,40 @@
 use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::io;
 use std::process::{Command, exit};
+use std::time::SystemTime;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as base64_engine;
 use blake3::Hasher;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use serde::{Serialize, Deserialize};
 use indexmap::IndexMap;
 use anyhow::{Result, Context, bail};
 
+/// A file's content hash alongside the cheap metadata used to short-circuit re-hashing it.
+#[derive(Serialize, Deserialize, Clone)]
+struct FileHash {
+    hash: String,
+    len: u64,
+    mtime: SystemTime,
+}
+
 #[derive(Serialize, Deserialize)]
 struct Hashes {
-    inputs: HashMap<String, String>,
-    outputs: HashMap<String, String>,
+    inputs: HashMap<String, FileHash>,
+    outputs: HashMap<String, FileHash>,
+    /// Base64-encoded stdout captured from the run that produced these hashes.
+    stdout: String,
+    /// Base64-encoded stderr captured from the run that produced these hashes.
+    stderr: String,
+    /// Exit code of the run that produced these hashes.
+    exit_code: i32,
+    /// When this run completed. Used to expire the cache entry when `--ttl` is set.
+    timestamp: SystemTime,
+    /// Hash of the resolved binary's canonical path, size and mtime, unless `--trust-binary` was set.
+    binary_hash: String,
 }
 
 /// Building INcrementally Based On Hashes
@@ -22,8 +44,28 @@ struct Hashes {
 /// Example: binboh -i input.txt -o output.txt -- mycommand -arg1 -arg2
 ///
 /// In this case, binboh will run mycommand -arg1 -arg2 if input.txt or output.txt have changed since the last run.
+///
+/// Inputs and outputs may also be glob patterns (`src/**/*.rs`) or directories, which are expanded
+/// relative to the working directory.
+///
+/// Run `binboh gc` to maintain the cache store itself (see `binboh gc --help`).
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, propagate_version = true)]
+struct Cli {
+    #[command(subcommand)]
+    subcommand: Option<Commands>,
+
+    #[clap(flatten)]
+    run: Args,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Maintain the binboh cache store: remove stale or orphaned entries.
+    Gc(GcArgs),
+}
+
+#[derive(clap::Args, Debug)]
 struct Args {
     /// Input files. Missing files are ignored.
     ///
@@ -36,6 +78,11 @@ struct Args {
     /// The more precisely you specify the inputs, the more powerful the caching will be.
     /// For example, if the command runs a script, and you specify the script as an input, the
     /// command will run if the script changes.
+    ///
+    /// A value containing `*`, `?` or `[` is expanded as a glob (e.g. `src/**/*.rs`), relative
+    /// to the working directory already hashed in `Args::hash`. A value naming a directory is
+    /// expanded recursively to the files it contains. The expanded set of files itself is folded
+    /// into the cache key, so adding or removing a matched file also triggers a rerun.
     #[clap(short, long = "input-files", value_name = "FILE", num_args=1..)]
     inputs: Vec<String>,
 
@@ -43,6 +90,13 @@ struct Args {
     ///
     /// Specifying no output files is valid. In this case, the command will only run when inputs
     /// change.
+    ///
+    /// A value containing `*`, `?` or `[`, or naming a directory, is also accepted here, but
+    /// unlike `--input-files` it is expanded *after* the command runs, against whatever files
+    /// exist at that point — expanding it up front would bake in an empty or stale match from
+    /// before the command had a chance to produce anything. The raw, unexpanded pattern is what
+    /// gets folded into the cache key; the post-run expansion only decides which files are
+    /// hashed and recorded for the next run's comparison.
     #[clap(short, long = "output-files", value_name = "FILE", num_args=1..)]
     outputs: Vec<String>,
 
@@ -50,14 +104,79 @@ struct Args {
     #[clap(long)]
     verbose: bool,
 
+    /// Treat a cached run older than this duration as stale and rerun the command.
+    ///
+    /// Accepts human-readable durations such as `30s`, `5m` or `1h`. Without this flag,
+    /// cache entries never expire based on age; only input/output hash changes force a rerun.
+    #[clap(long, value_name = "DURATION")]
+    ttl: Option<humantime::Duration>,
+
+    /// Do not resolve and hash the invoked binary itself.
+    ///
+    /// By default binboh resolves `command[0]` on `PATH`, records a hash of its canonical path,
+    /// size and modification time with each cache entry, and reruns the command when that hash
+    /// differs, so upgrading the wrapped tool (e.g. a new `protoc` or compiler) invalidates
+    /// stale results. Pass this flag to opt out, e.g. when
+    /// the binary's mtime is unstable or it isn't resolvable on `PATH`.
+    #[clap(long)]
+    trust_binary: bool,
+
+    /// Trust a file's size and modification time instead of re-hashing its content.
+    ///
+    /// By default binboh always reads and hashes the full content of every input/output file.
+    /// With this flag, if a file's `(len, mtime)` matches the previous run, binboh reuses the
+    /// stored hash instead of re-reading the file. Do not use this if you rewrite files in place
+    /// such that the content changes but the size and mtime don't.
+    #[clap(long)]
+    trust_mtime: bool,
+
+    /// Hash only the first N bytes of each input/output file, plus its total length.
+    ///
+    /// This trades a small correctness window (a change deep inside a huge file past the
+    /// first N bytes goes undetected) for a large speedup on huge media/data blobs whose
+    /// prefixes reliably change when the file changes. Without this flag, the full file
+    /// content is streamed through the hasher.
+    #[clap(long, value_name = "N")]
+    head_bytes: Option<u64>,
+
     /// Command to run.
     ///
     /// The first argument is the binary to be called, the rest are arguments to that binary.
     /// You can specify the command after a double dash to avoid parsing issues.
-    #[clap(num_args=1.., required=true, last=true, value_name = "COMMAND")]
+    #[clap(num_args=1.., last=true, value_name = "COMMAND")]
     command: Vec<String>,
 }
 
+/// Maintain the binboh cache store.
+///
+/// Scans the cache directory and removes entries older than `--max-age`, entries whose
+/// recorded input/output files no longer exist (with `--prune-missing`), or the oldest
+/// entries beyond `--max-entries`.
+#[derive(clap::Args, Debug)]
+struct GcArgs {
+    /// Remove cache entries whose run is older than this duration (e.g. `30d`, `12h`).
+    #[clap(long, value_name = "DURATION")]
+    max_age: Option<humantime::Duration>,
+
+    /// Remove cache entries whose recorded input or output files no longer exist.
+    #[clap(long)]
+    prune_missing: bool,
+
+    /// Trim the cache to at most this many entries, removing the oldest first.
+    ///
+    /// There is no equivalent flag to trim by total cache size yet, only by entry count.
+    #[clap(long, value_name = "N")]
+    max_entries: Option<usize>,
+
+    /// Print what would be removed without deleting anything.
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Print debug information.
+    #[clap(long)]
+    verbose: bool,
+}
+
 impl Args {
     fn hash(&self) -> Result<String> {
         // IndexMap so that insertion order is preserved. This is important since we just dump the map
@@ -85,27 +204,95 @@ impl Args {
         Ok(hasher.finalize().to_hex().to_string())
     }
 
-    fn needs_to_run(&self, input_hashes: &HashMap<String, String>, previous_run: Option<&Hashes>) -> bool {
+    fn needs_to_run(&self, input_hashes: &HashMap<String, FileHash>, previous_run: Option<&Hashes>) -> Result<bool> {
         if let Some(prev) = previous_run {
+            if let Some(ttl) = self.ttl {
+                let age = prev.timestamp.elapsed().unwrap_or(std::time::Duration::MAX);
+                if age > *ttl {
+                    self.if_verbose(|| eprintln!("Previous run is older than TTL ({} > {}). Need to rerun.", humantime::format_duration(age), ttl));
+                    return Ok(true);
+                }
+                self.if_verbose(|| eprintln!("Previous run is within TTL ({} <= {}).", humantime::format_duration(age), ttl));
+            }
+            if self.hash_binary()? != prev.binary_hash {
+                self.if_verbose(|| eprintln!("Binary hash differs. Need to rerun."));
+                return Ok(true);
+            }
+            self.if_verbose(|| eprintln!("Binary hash matches."));
             for input_file in &self.inputs {
-                if input_hashes[input_file] != prev.inputs[input_file] {
+                if input_hashes[input_file].hash != prev.inputs[input_file].hash {
                     self.if_verbose(|| eprintln!("Input file hash differs: {}", input_file));
-                    return true;
+                    return Ok(true);
                 }
                 self.if_verbose(|| eprintln!("Input file hash matches: {}", input_file));
             }
-            for output_file in &self.outputs {
-                if self.hash_file(output_file, Some("doesnotexist")).unwrap() != prev.outputs[output_file] {
+            for (output_file, previous) in &prev.outputs {
+                if self.hash_file(output_file, Some("doesnotexist"), Some(previous))?.hash != previous.hash {
                     self.if_verbose(|| eprintln!("Output file hash differs: {}", output_file));
-                    return true;
+                    return Ok(true);
                 }
                 self.if_verbose(|| eprintln!("Output file hash matches: {}", output_file));
             }
         } else {
             self.if_verbose(|| eprintln!("No previous run found. Need to rerun."));
-            return true;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn hash_binary(&self) -> Result<String> {
+        if self.trust_binary {
+            return Ok("trusted".to_string());
         }
-        return false;
+        let resolved = which::which(&self.command[0])
+            .with_context(|| format!("Failed to resolve binary on PATH: {}", self.command[0]))?;
+        let canonical = resolved.canonicalize().unwrap_or_else(|_| resolved.clone());
+        let metadata = fs::metadata(&canonical)
+            .with_context(|| format!("Failed to stat binary {}", canonical.to_string_lossy()))?;
+        let mtime = metadata.modified()
+            .with_context(|| format!("Failed to get mtime of binary {}", canonical.to_string_lossy()))?;
+        let descriptor = format!("{}:{}:{:?}", canonical.to_string_lossy(), metadata.len(), mtime);
+        self.if_verbose(|| eprintln!("Hashing binary {}: {}", self.command[0], descriptor));
+        let mut hasher = Hasher::new();
+        hasher.update(descriptor.as_bytes());
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+
+    fn expand_paths(&self, patterns: &[String]) -> Result<Vec<String>> {
+        let mut expanded = Vec::new();
+        for pattern in patterns {
+            let path = std::path::Path::new(pattern);
+            if path.is_dir() {
+                self.if_verbose(|| eprintln!("Expanding directory: {}", pattern));
+                self.expand_directory(path, &mut expanded)?;
+            } else if pattern.contains(['*', '?', '[']) {
+                self.if_verbose(|| eprintln!("Expanding glob: {}", pattern));
+                for entry in glob::glob(pattern).with_context(|| format!("Invalid glob pattern: {}", pattern))? {
+                    let entry = entry.with_context(|| format!("Failed to read glob match for {}", pattern))?;
+                    if entry.is_file() {
+                        expanded.push(entry.to_string_lossy().to_string());
+                    }
+                }
+            } else {
+                expanded.push(pattern.clone());
+            }
+        }
+        expanded.sort();
+        expanded.dedup();
+        Ok(expanded)
+    }
+
+    fn expand_directory(&self, dir: &std::path::Path, out: &mut Vec<String>) -> Result<()> {
+        for entry in fs::read_dir(dir).with_context(|| format!("Failed to read directory {}", dir.to_string_lossy()))? {
+            let entry = entry.with_context(|| format!("Failed to read directory entry in {}", dir.to_string_lossy()))?;
+            let path = entry.path();
+            if path.is_dir() {
+                self.expand_directory(&path, out)?;
+            } else {
+                out.push(path.to_string_lossy().to_string());
+            }
+        }
+        Ok(())
     }
 
     fn if_verbose(&self, f: impl FnOnce()) {
@@ -114,16 +301,15 @@ impl Args {
         }
     }
 
-    fn hash_file(&self, file_path: &str, fallback: Option<&str>) -> Result<String> {
-        self.if_verbose(|| eprintln!("Hashing file content: {}", file_path));
-        let mut file = match fs::File::open(file_path) {
+    fn hash_file(&self, file_path: &str, fallback: Option<&str>, previous: Option<&FileHash>) -> Result<FileHash> {
+        let file = match fs::File::open(file_path) {
             Ok(file) => file,
             Err(e) => {
                 if e.kind() == std::io::ErrorKind::NotFound {
                     match fallback {
                         Some(f) => {
                             self.if_verbose(|| eprintln!("File does not exist. Using fallback for hashing: {}", file_path));
-                            return Ok(f.to_string())
+                            return Ok(FileHash { hash: f.to_string(), len: 0, mtime: SystemTime::UNIX_EPOCH })
                         },
                         None => bail!("File does not exist: {}", file_path)
                     }
@@ -132,22 +318,144 @@ impl Args {
                 }
             }
         };
+        let metadata = file.metadata()
+            .with_context(|| format!("Failed to stat file {}", file_path))?;
+        let len = metadata.len();
+        let mtime = metadata.modified()
+            .with_context(|| format!("Failed to get mtime of file {}", file_path))?;
+
+        if self.trust_mtime {
+            if let Some(prev) = previous {
+                if prev.len == len && prev.mtime == mtime {
+                    self.if_verbose(|| eprintln!("File size and mtime unchanged, trusting previous hash: {}", file_path));
+                    return Ok(prev.clone());
+                }
+            }
+        }
+
         let mut hasher = Hasher::new();
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)
-            .with_context(|| format!("Failed to read file {}", file_path))?;
-        hasher.update(&buffer);
+        let mut reader: Box<dyn Read> = match self.head_bytes {
+            Some(n) => {
+                self.if_verbose(|| eprintln!("Hashing first {} bytes of file: {}", n, file_path));
+                Box::new(file.take(n))
+            },
+            None => {
+                self.if_verbose(|| eprintln!("Hashing file content: {}", file_path));
+                Box::new(file)
+            },
+        };
+        let mut buffer = [0u8; 65536];
+        loop {
+            let read = reader.read(&mut buffer)
+                .with_context(|| format!("Failed to read file {}", file_path))?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        if self.head_bytes.is_some() {
+            hasher.update(&len.to_le_bytes());
+        }
         let hash = hasher.finalize().to_hex().to_string();
         self.if_verbose(|| eprintln!("Hash: {}", hash));
-        Ok(hash)
+        Ok(FileHash { hash, len, mtime })
+    }
+}
+
+fn run_gc(gc: &GcArgs, cache_dir: &std::path::Path) -> Result<()> {
+    if !cache_dir.exists() {
+        if gc.verbose {
+            eprintln!("Cache directory does not exist: {}", cache_dir.to_string_lossy());
+        }
+        println!("Removed 0 of 0 cache entries.");
+        return Ok(());
+    }
+
+    let mut entries = Vec::new();
+    collect_cache_entries(cache_dir, &mut entries)?;
+
+    let mut to_remove = Vec::new();
+    let mut kept = Vec::new();
+
+    for path in entries {
+        let file = fs::File::open(&path)
+            .with_context(|| format!("Failed to open cache entry {}", path.to_string_lossy()))?;
+        let hashes: Option<Hashes> = serde_json::from_reader(file).ok();
+
+        let keep = match &hashes {
+            None => false,
+            Some(hashes) => {
+                let age_ok = match gc.max_age {
+                    Some(max_age) => hashes.timestamp.elapsed().unwrap_or(std::time::Duration::MAX) <= *max_age,
+                    None => true,
+                };
+                let files_ok = !gc.prune_missing || hashes.inputs.keys().chain(hashes.outputs.keys())
+                    .all(|f| std::path::Path::new(f).exists());
+                age_ok && files_ok
+            }
+        };
+
+        if gc.verbose {
+            eprintln!("{} cache entry: {}", if keep { "Keeping" } else { "Removing" }, path.to_string_lossy());
+        }
+
+        if keep {
+            let timestamp = hashes.as_ref().map(|h| h.timestamp).unwrap_or(SystemTime::UNIX_EPOCH);
+            kept.push((path, timestamp));
+        } else {
+            to_remove.push(path);
+        }
+    }
+
+    if let Some(max_entries) = gc.max_entries {
+        kept.sort_by_key(|(_, timestamp)| *timestamp);
+        while kept.len() > max_entries {
+            to_remove.push(kept.remove(0).0);
+        }
+    }
+
+    for path in &to_remove {
+        if gc.dry_run {
+            println!("Would remove: {}", path.to_string_lossy());
+        } else {
+            fs::remove_file(path)
+                .with_context(|| format!("Failed to remove cache entry {}", path.to_string_lossy()))?;
+        }
+    }
+
+    let verb = if gc.dry_run { "Would remove" } else { "Removed" };
+    println!("{} {} of {} cache entries.", verb, to_remove.len(), to_remove.len() + kept.len());
+
+    Ok(())
+}
+
+fn collect_cache_entries(dir: &std::path::Path, out: &mut Vec<std::path::PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read cache directory {}", dir.to_string_lossy()))? {
+        let entry = entry.with_context(|| format!("Failed to read cache directory entry in {}", dir.to_string_lossy()))?;
+        let path = entry.path();
+        if path.is_dir() {
+            collect_cache_entries(&path, out)?;
+        } else if path.extension().and_then(|e| e.to_str()) == Some("json") {
+            out.push(path);
+        }
     }
+    Ok(())
 }
 
 fn main() -> Result<()> {
-    let call = Args::parse();
+    let cli = Cli::parse();
+    if let Some(Commands::Gc(gc_args)) = cli.subcommand {
+        let cache_dir = dirs::cache_dir()
+            .with_context(|| "Could not find the user's cache directory.")?
+            .join("binboh");
+        return run_gc(&gc_args, &cache_dir);
+    }
+
+    let mut call = cli.run;
     if call.command.is_empty() {
         bail!("No command specified");
     }
+    call.inputs = call.expand_paths(&call.inputs)?;
 
     let cache_dir = dirs::cache_dir()
         .with_context(|| "Could not find the user's cache directory.")?
@@ -169,25 +477,47 @@ fn main() -> Result<()> {
         None
     };
 
-    let input_hashes = call.inputs.iter().map(|f| call.hash_file(f, Some("doesnotexist")).map(|h| (f.clone(), h))).collect::<Result<HashMap<String,String>>>()?;
-    if !call.needs_to_run(&input_hashes, previous_run.as_ref()) {
-        println!("Skipped: {}", call.command.join(" "));
-        return Ok(());
+    let input_hashes = call.inputs.iter()
+        .map(|f| call.hash_file(f, Some("doesnotexist"), previous_run.as_ref().and_then(|p| p.inputs.get(f))).map(|h| (f.clone(), h)))
+        .collect::<Result<HashMap<String,FileHash>>>()?;
+    if !call.needs_to_run(&input_hashes, previous_run.as_ref())? {
+        let prev = previous_run.as_ref().unwrap();
+        call.if_verbose(|| eprintln!("Skipped: {}", call.command.join(" ")));
+        io::stdout().write_all(&base64_engine.decode(&prev.stdout).with_context(|| "Failed to decode cached stdout")?)
+            .with_context(|| "Failed to replay cached stdout")?;
+        io::stderr().write_all(&base64_engine.decode(&prev.stderr).with_context(|| "Failed to decode cached stderr")?)
+            .with_context(|| "Failed to replay cached stderr")?;
+        exit(prev.exit_code);
     }
 
     call.if_verbose(|| eprintln!("Running command: {}", call.command.join(" ")));
-    let status = Command::new(&call.command[0])
+    let output = Command::new(&call.command[0])
         .args(&call.command[1..])
-        .status()
+        .output()
         .with_context(|| format!("Failed to run command {}", call.command.join(" ")))?;
 
-    if !status.success() {
-        exit(status.code().unwrap_or(1));
+    io::stdout().write_all(&output.stdout).with_context(|| "Failed to write command stdout")?;
+    io::stderr().write_all(&output.stderr).with_context(|| "Failed to write command stderr")?;
+
+    if !output.status.success() {
+        exit(output.status.code().unwrap_or(1));
     }
 
+    // Outputs are expanded after the command has run, not before: a glob/directory pattern
+    // like `build/*.o` matches nothing on the first run, so expanding it up front would bake
+    // an empty (or stale) file set into the cache key and force a spurious rerun the moment
+    // the command actually produces something.
+    let output_paths = call.expand_paths(&call.outputs)?;
     let hashes = Hashes {
         inputs:  input_hashes,
-        outputs: call.outputs.iter().map(|f| call.hash_file(f, Some("doesnotexist")).map(|h| (f.clone(), h))).collect::<Result<HashMap<String,String>>>()?,
+        outputs: output_paths.iter()
+            .map(|f| call.hash_file(f, Some("doesnotexist"), previous_run.as_ref().and_then(|p| p.outputs.get(f))).map(|h| (f.clone(), h)))
+            .collect::<Result<HashMap<String,FileHash>>>()?,
+        stdout: base64_engine.encode(&output.stdout),
+        stderr: base64_engine.encode(&output.stderr),
+        exit_code: output.status.code().unwrap_or(0),
+        timestamp: SystemTime::now(),
+        binary_hash: call.hash_binary()?,
     };
 
     call.if_verbose(|| eprintln!("Writing hashes to: {}", cache_file.to_string_lossy()));
@@ -201,3 +531,241 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn unique_temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("binboh-test-{}-{}-{:?}", name, std::process::id(), std::thread::current().id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn sample_hashes(timestamp: SystemTime, outputs: HashMap<String, FileHash>) -> Hashes {
+        Hashes {
+            inputs: HashMap::new(),
+            outputs,
+            stdout: String::new(),
+            stderr: String::new(),
+            exit_code: 0,
+            timestamp,
+            binary_hash: "test".to_string(),
+        }
+    }
+
+    fn write_entry(cache_dir: &std::path::Path, name: &str, hashes: &Hashes) -> std::path::PathBuf {
+        let path = cache_dir.join(format!("{}.json", name));
+        let file = fs::File::create(&path).unwrap();
+        serde_json::to_writer(file, hashes).unwrap();
+        path
+    }
+
+    fn gc_args(max_age: Option<humantime::Duration>, prune_missing: bool, max_entries: Option<usize>) -> GcArgs {
+        GcArgs { max_age, prune_missing, max_entries, dry_run: false, verbose: false }
+    }
+
+    fn base_args() -> Args {
+        Args { inputs: vec![], outputs: vec![], verbose: false, ttl: None, trust_binary: false, trust_mtime: false, head_bytes: None, command: vec!["true".to_string()] }
+    }
+
+    #[test]
+    fn gc_removes_entries_older_than_max_age() {
+        let dir = unique_temp_dir("gc-max-age");
+        let old = write_entry(&dir, "old", &sample_hashes(SystemTime::now() - Duration::from_secs(3600), HashMap::new()));
+        let fresh = write_entry(&dir, "fresh", &sample_hashes(SystemTime::now(), HashMap::new()));
+
+        run_gc(&gc_args(Some("30m".parse().unwrap()), false, None), &dir).unwrap();
+
+        assert!(!old.exists());
+        assert!(fresh.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn gc_removes_entries_with_missing_files() {
+        let dir = unique_temp_dir("gc-prune-missing");
+        let mut outputs = HashMap::new();
+        outputs.insert("/does/not/exist-binboh-test.txt".to_string(), FileHash { hash: "h".to_string(), len: 0, mtime: SystemTime::UNIX_EPOCH });
+        let missing = write_entry(&dir, "missing", &sample_hashes(SystemTime::now(), outputs));
+        let present = write_entry(&dir, "present", &sample_hashes(SystemTime::now(), HashMap::new()));
+
+        run_gc(&gc_args(None, true, None), &dir).unwrap();
+
+        assert!(!missing.exists());
+        assert!(present.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn gc_trims_to_max_entries_by_recorded_timestamp_not_file_mtime() {
+        let dir = unique_temp_dir("gc-max-entries");
+        let oldest = write_entry(&dir, "oldest", &sample_hashes(SystemTime::now() - Duration::from_secs(300), HashMap::new()));
+        let middle = write_entry(&dir, "middle", &sample_hashes(SystemTime::now() - Duration::from_secs(200), HashMap::new()));
+        let newest = write_entry(&dir, "newest", &sample_hashes(SystemTime::now() - Duration::from_secs(100), HashMap::new()));
+
+        // Touching the cache files' own mtimes in reverse order of their recorded timestamps
+        // would make a file-mtime-based trim keep the wrong entries; gc must go by
+        // `Hashes::timestamp` instead.
+        fs::OpenOptions::new().write(true).open(&newest).unwrap().set_modified(SystemTime::now() - Duration::from_secs(500)).unwrap();
+
+        run_gc(&gc_args(None, false, Some(2)), &dir).unwrap();
+
+        assert!(!oldest.exists());
+        assert!(middle.exists());
+        assert!(newest.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expand_paths_recurses_into_directories() {
+        let dir = unique_temp_dir("expand-dir");
+        fs::write(dir.join("a.txt"), b"a").unwrap();
+        let nested = dir.join("nested");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(nested.join("b.txt"), b"b").unwrap();
+
+        let args = Args { inputs: vec![], outputs: vec![], verbose: false, ttl: None, trust_binary: false, trust_mtime: false, head_bytes: None, command: vec!["true".to_string()] };
+        let mut expanded = args.expand_paths(&[dir.to_string_lossy().to_string()]).unwrap();
+        expanded.sort();
+
+        assert_eq!(expanded.len(), 2);
+        assert!(expanded.iter().any(|p| p.ends_with("a.txt")));
+        assert!(expanded.iter().any(|p| p.ends_with("b.txt")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expand_paths_expands_globs() {
+        let dir = unique_temp_dir("expand-glob");
+        fs::write(dir.join("one.rs"), b"").unwrap();
+        fs::write(dir.join("two.rs"), b"").unwrap();
+        fs::write(dir.join("three.txt"), b"").unwrap();
+
+        let args = Args { inputs: vec![], outputs: vec![], verbose: false, ttl: None, trust_binary: false, trust_mtime: false, head_bytes: None, command: vec!["true".to_string()] };
+        let pattern = dir.join("*.rs").to_string_lossy().to_string();
+        let expanded = args.expand_paths(&[pattern]).unwrap();
+
+        assert_eq!(expanded.len(), 2);
+        assert!(expanded.iter().all(|p| p.ends_with(".rs")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expand_paths_keeps_literal_paths_untouched() {
+        let args = Args { inputs: vec![], outputs: vec![], verbose: false, ttl: None, trust_binary: false, trust_mtime: false, head_bytes: None, command: vec!["true".to_string()] };
+        let expanded = args.expand_paths(&["does/not/exist.txt".to_string()]).unwrap();
+        assert_eq!(expanded, vec!["does/not/exist.txt".to_string()]);
+    }
+
+    #[test]
+    fn hash_file_trusts_mtime_over_content_when_trust_mtime_is_set() {
+        let dir = unique_temp_dir("trust-mtime");
+        let path = dir.join("data.txt");
+        fs::write(&path, b"original").unwrap();
+
+        let args = Args { trust_mtime: true, ..base_args() };
+        let original = args.hash_file(path.to_str().unwrap(), None, None).unwrap();
+
+        // Rewrite the content in place but restore the original mtime, exactly the scenario
+        // --trust-mtime's doc comment warns against: (len, mtime) still matches the previous
+        // run, so the stale hash is reused instead of detecting the content change.
+        fs::write(&path, b"mutated!").unwrap();
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_modified(original.mtime).unwrap();
+
+        let reused = args.hash_file(path.to_str().unwrap(), None, Some(&original)).unwrap();
+
+        assert_eq!(reused.hash, original.hash);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hash_file_head_bytes_mixes_in_total_length() {
+        let dir = unique_temp_dir("head-bytes");
+        let short = dir.join("short.bin");
+        let long = dir.join("long.bin");
+        let short_twin = dir.join("short-twin.bin");
+        fs::write(&short, b"abcdefghij").unwrap();
+        fs::write(&long, b"abcdefghijklmnop").unwrap();
+        fs::write(&short_twin, b"abcdefghij").unwrap();
+
+        let args = Args { head_bytes: Some(10), ..base_args() };
+        let short_hash = args.hash_file(short.to_str().unwrap(), None, None).unwrap();
+        let long_hash = args.hash_file(long.to_str().unwrap(), None, None).unwrap();
+        let short_twin_hash = args.hash_file(short_twin.to_str().unwrap(), None, None).unwrap();
+
+        // `long` shares its first 10 bytes with `short` but differs in total length, so mixing
+        // `len` into the hash must tell them apart even though the hashed prefix is identical.
+        assert_ne!(short_hash.hash, long_hash.hash);
+        // Two files identical within the first N bytes and in total length hash the same.
+        assert_eq!(short_hash.hash, short_twin_hash.hash);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn needs_to_run_respects_ttl_boundary() {
+        // trust_binary makes hash_binary() return "trusted" so it matches the recorded
+        // binary_hash below, isolating the TTL branch from the binary-invalidation check.
+        let args = Args { ttl: Some("30m".parse().unwrap()), trust_binary: true, ..base_args() };
+        let prev_at = |timestamp| Hashes { binary_hash: "trusted".to_string(), ..sample_hashes(timestamp, HashMap::new()) };
+
+        let within_ttl = prev_at(SystemTime::now() - Duration::from_secs(60));
+        assert!(!args.needs_to_run(&HashMap::new(), Some(&within_ttl)).unwrap());
+
+        let past_ttl = prev_at(SystemTime::now() - Duration::from_secs(3600));
+        assert!(args.needs_to_run(&HashMap::new(), Some(&past_ttl)).unwrap());
+    }
+
+    #[test]
+    fn hash_binary_changes_when_the_resolved_binary_mtime_changes() {
+        let dir = unique_temp_dir("hash-binary");
+        let binary_path = dir.join("mybinary");
+        fs::write(&binary_path, b"v1").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&binary_path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        // which::which resolves a candidate containing a path separator directly instead of
+        // searching PATH, so this exercises the real resolution path without touching PATH.
+        let args = Args { command: vec![binary_path.to_string_lossy().to_string()], ..base_args() };
+        let before = args.hash_binary().unwrap();
+
+        let file = fs::OpenOptions::new().write(true).open(&binary_path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+
+        let after = args.hash_binary().unwrap();
+        assert_ne!(before, after);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hash_binary_short_circuits_when_trust_binary_is_set() {
+        let args = Args { trust_binary: true, command: vec!["does-not-exist-binboh-test".to_string()], ..base_args() };
+        assert_eq!(args.hash_binary().unwrap(), "trusted");
+    }
+
+    #[test]
+    fn cached_output_round_trips_exit_code_and_binary_safe_output_through_base64() {
+        // Arbitrary non-UTF8 bytes, like a tool writing compressed or encoded data to stdout,
+        // plus a non-zero exit code — both must survive the cache's base64 round trip intact.
+        let stdout = vec![0u8, 159, 146, 150, 10];
+        let stderr = b"warning: something\n".to_vec();
+        let exit_code = 17;
+
+        let hashes = Hashes {
+            stdout: base64_engine.encode(&stdout),
+            stderr: base64_engine.encode(&stderr),
+            exit_code,
+            ..sample_hashes(SystemTime::now(), HashMap::new())
+        };
+
+        assert_eq!(base64_engine.decode(&hashes.stdout).unwrap(), stdout);
+        assert_eq!(base64_engine.decode(&hashes.stderr).unwrap(), stderr);
+        assert_eq!(hashes.exit_code, exit_code);
+    }
+}
+